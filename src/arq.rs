@@ -0,0 +1,201 @@
+//! Automatic-repeat-request (ARQ) bookkeeping: the reliability/ordering
+//! semantics layered on top of raw, lossy UDP datagrams.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use crate::datatype::{read_u24, write_u24};
+use crate::error::{RaknetError, Result};
+
+/// How a frame of application data should be delivered. Mirrors the RakNet
+/// wire values, so the discriminant doubles as the on-the-wire reliability
+/// byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reliability {
+    Unreliable = 0,
+    UnreliableSequenced = 1,
+    Reliable = 2,
+    ReliableOrdered = 3,
+    ReliableSequenced = 4,
+}
+
+impl Reliability {
+    pub fn is_reliable(self) -> bool {
+        matches!(
+            self,
+            Reliability::Reliable | Reliability::ReliableOrdered | Reliability::ReliableSequenced
+        )
+    }
+
+    pub fn is_sequenced(self) -> bool {
+        matches!(
+            self,
+            Reliability::UnreliableSequenced | Reliability::ReliableSequenced
+        )
+    }
+
+    pub fn is_ordered(self) -> bool {
+        matches!(self, Reliability::ReliableOrdered)
+    }
+}
+
+impl TryFrom<u8> for Reliability {
+    type Error = RaknetError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Reliability::Unreliable),
+            1 => Ok(Reliability::UnreliableSequenced),
+            2 => Ok(Reliability::Reliable),
+            3 => Ok(Reliability::ReliableOrdered),
+            4 => Ok(Reliability::ReliableSequenced),
+            _ => Err(RaknetError::IncorrectReliability),
+        }
+    }
+}
+
+/// Identifies one piece of a fragmented frame.
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentInfo {
+    pub compound_size: u32,
+    pub compound_id: u16,
+    pub index: u32,
+}
+
+/// A single frame of application data, as carried inside a `FrameSet`
+/// datagram.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub reliability: Reliability,
+    pub reliable_frame_index: Option<u32>,
+    pub sequenced_frame_index: Option<u32>,
+    pub ordered_frame_index: Option<u32>,
+    pub order_channel: u8,
+    pub fragment: Option<FragmentInfo>,
+    pub body: Vec<u8>,
+}
+
+/// One or more frames batched into a single datagram, tagged with a
+/// monotonic sequence number so the peer can ACK/NAK it.
+///
+/// `sender_guid` carries the connection's identifying GUID (the one
+/// exchanged during the connect handshake) on every datagram, not just the
+/// handshake itself, so the receiving side can recognize a peer whose
+/// `SocketAddr` has changed (see connection migration in `server`).
+#[derive(Debug, Clone)]
+pub struct FrameSet {
+    pub sequence_number: u32,
+    pub sender_guid: u64,
+    pub frames: Vec<Frame>,
+}
+
+/// Tracks frames this side has sent reliably so they can be retransmitted
+/// until ACKed, plus the per-channel counters needed to stamp new frames.
+pub struct SendQueue {
+    pub next_sequence_number: u32,
+    pub next_reliable_index: u32,
+    pub next_sequenced_index: HashMap<u8, u32>,
+    pub next_ordered_index: HashMap<u8, u32>,
+    pub next_compound_id: u16,
+    unacked: BTreeMap<u32, (Instant, FrameSet)>,
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        Self {
+            next_sequence_number: 0,
+            next_reliable_index: 0,
+            next_sequenced_index: HashMap::new(),
+            next_ordered_index: HashMap::new(),
+            next_compound_id: 0,
+            unacked: BTreeMap::new(),
+        }
+    }
+}
+
+impl SendQueue {
+    pub fn track(&mut self, now: Instant, frameset: FrameSet) {
+        self.unacked.insert(frameset.sequence_number, (now, frameset));
+    }
+
+    pub fn ack(&mut self, sequence_number: u32) {
+        self.unacked.remove(&sequence_number);
+    }
+
+    /// The send time of the longest-outstanding unacked frameset, if any.
+    pub fn earliest_unacked(&self) -> Option<Instant> {
+        self.unacked.values().map(|(sent_at, _)| *sent_at).min()
+    }
+
+    /// Returns framesets whose retransmission timer has expired.
+    pub fn expired(&mut self, now: Instant, rto: std::time::Duration) -> Vec<FrameSet> {
+        let mut out = Vec::new();
+        for (sent_at, frameset) in self.unacked.values_mut() {
+            if now.duration_since(*sent_at) >= rto {
+                *sent_at = now;
+                out.push(frameset.clone());
+            }
+        }
+        out
+    }
+}
+
+/// De-duplicates and re-orders frames arriving from the peer before they're
+/// handed to the application.
+#[derive(Default)]
+pub struct RecvQueue {
+    seen_reliable: std::collections::HashSet<u32>,
+    next_sequenced: HashMap<u8, u32>,
+    next_ordered: HashMap<u8, u32>,
+    ordered_holes: HashMap<u8, BTreeMap<u32, Vec<u8>>>,
+}
+
+impl RecvQueue {
+    /// Feed in one already-defragmented frame. Returns the payloads that are
+    /// now ready to deliver to the application, in delivery order.
+    pub fn accept(&mut self, frame: &Frame) -> Vec<Vec<u8>> {
+        if let Some(index) = frame.reliable_frame_index {
+            if !self.seen_reliable.insert(index) {
+                return Vec::new();
+            }
+        }
+
+        if frame.reliability.is_sequenced() {
+            let next = self.next_sequenced.entry(frame.order_channel).or_insert(0);
+            let index = frame.sequenced_frame_index.unwrap_or(0);
+            if index < *next {
+                return Vec::new();
+            }
+            *next = index + 1;
+            return vec![frame.body.clone()];
+        }
+
+        if frame.reliability.is_ordered() {
+            let next = self.next_ordered.entry(frame.order_channel).or_insert(0);
+            let index = frame.ordered_frame_index.unwrap_or(0);
+            let holes = self.ordered_holes.entry(frame.order_channel).or_default();
+
+            if index < *next {
+                return Vec::new();
+            }
+            holes.insert(index, frame.body.clone());
+
+            let mut out = Vec::new();
+            while let Some(body) = holes.remove(next) {
+                out.push(body);
+                *next += 1;
+            }
+            return out;
+        }
+
+        vec![frame.body.clone()]
+    }
+}
+
+pub(crate) fn encode_u24(value: u32) -> [u8; 3] {
+    write_u24(value)
+}
+
+pub(crate) fn decode_u24(buf: &[u8]) -> Result<u32> {
+    read_u24(buf)
+}
@@ -0,0 +1,243 @@
+//! Opt-in transport encryption for an established `RaknetSocket`.
+//!
+//! Once the ordinary connection handshake (`ConnectionRequest` /
+//! `ConnectionRequestAccepted`) has finished, both
+//! peers may additionally exchange ephemeral X25519 public keys over a
+//! reliable-ordered control frame (`packet::PacketEncryptionHandshake`) and
+//! derive a shared 32-byte key with HKDF-SHA256. From that point on, every
+//! UDP datagram the socket sends is authenticated and encrypted with
+//! ChaCha20-Poly1305, built up from the individual ChaCha20 stream cipher
+//! and Poly1305 MAC rather than a combined AEAD construction, so the wire
+//! format (`counter || ciphertext || tag`) is fully explicit here.
+//!
+//! Unconnected pings/pongs and `ConnectionRequest`/`ConnectionRequestAccepted`
+//! always stay in cleartext: the key doesn't exist until after the
+//! handshake completes.
+
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::ChaCha20;
+use hkdf::Hkdf;
+use poly1305::{
+    universal_hash::{KeyInit, UniversalHash},
+    Poly1305,
+};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::error::{RaknetError, Result};
+
+const NONCE_COUNTER_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+/// How far behind the highest counter [`ReplayGuard`] has seen a datagram's
+/// counter may still be and be trusted: wide enough to tolerate ordinary UDP
+/// reordering, narrow enough to bound the tracking set's size.
+const REPLAY_WINDOW: u64 = 64;
+
+/// One side's half of the X25519 key exchange, held only until the shared
+/// key has been derived.
+pub struct Handshake {
+    secret: EphemeralSecret,
+    pub public_key: [u8; 32],
+}
+
+impl Handshake {
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random();
+        let public_key = PublicKey::from(&secret).to_bytes();
+        Self { secret, public_key }
+    }
+
+    /// Consume the handshake and the peer's public key to derive the shared
+    /// AEAD key via X25519 ECDH + HKDF-SHA256.
+    pub fn derive_key(self, peer_public_key: &[u8; 32]) -> [u8; 32] {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(*peer_public_key));
+        let hk = Hkdf::<Sha256>::new(None, shared.as_bytes());
+        let mut key = [0u8; 32];
+        hk.expand(b"rust-raknet transport key", &mut key)
+            .expect("32 bytes is a valid HKDF output length");
+        key
+    }
+}
+
+/// Builds the 12-byte ChaCha20-Poly1305 nonce from a per-direction
+/// monotonically increasing counter, zero-padded in the high bytes.
+fn build_nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..12].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+fn poly1305_key(key: &[u8; 32], nonce: &[u8; 12]) -> [u8; 32] {
+    let mut cipher = ChaCha20::new(key.into(), nonce.into());
+    let mut block = [0u8; 64];
+    cipher.apply_keystream(&mut block);
+    block[..32].try_into().unwrap()
+}
+
+/// Encrypts `plaintext` under `key` using the connection's current send
+/// counter, bumps the counter, and returns `counter || ciphertext || tag`
+/// ready to hand to `send_to`.
+pub fn seal(key: &[u8; 32], counter: &mut u64, plaintext: &[u8]) -> Vec<u8> {
+    let nonce = build_nonce(*counter);
+    let mac_key = poly1305_key(key, &nonce);
+
+    let mut ciphertext = plaintext.to_vec();
+    let mut cipher = ChaCha20::new(key.into(), &nonce.into());
+    // Block counter 0 was consumed generating the Poly1305 key, so the
+    // keystream used for the message itself starts at block 1.
+    cipher.seek(64u32);
+    cipher.apply_keystream(&mut ciphertext);
+
+    let mut mac = Poly1305::new((&mac_key).into());
+    mac.update_padded(&ciphertext);
+    let tag = mac.finalize();
+
+    let mut out = Vec::with_capacity(NONCE_COUNTER_LEN + ciphertext.len() + TAG_LEN);
+    out.extend_from_slice(&counter.to_be_bytes());
+    out.extend_from_slice(&ciphertext);
+    out.extend_from_slice(&tag);
+
+    *counter += 1;
+    out
+}
+
+/// Verifies and decrypts a datagram of the form `counter || ciphertext ||
+/// tag`. A tag mismatch is reported as `DecryptionFailure` so the caller can
+/// drop the datagram silently rather than let a tampered packet reach the
+/// parser.
+pub fn open(key: &[u8; 32], datagram: &[u8]) -> Result<Vec<u8>> {
+    if datagram.len() < NONCE_COUNTER_LEN + TAG_LEN {
+        return Err(RaknetError::TooSmallPacketLength);
+    }
+
+    let counter = u64::from_be_bytes(datagram[..NONCE_COUNTER_LEN].try_into().unwrap());
+    let ciphertext = &datagram[NONCE_COUNTER_LEN..datagram.len() - TAG_LEN];
+    let tag = &datagram[datagram.len() - TAG_LEN..];
+
+    let nonce = build_nonce(counter);
+    let mac_key = poly1305_key(key, &nonce);
+
+    let mut mac = Poly1305::new((&mac_key).into());
+    mac.update_padded(ciphertext);
+    // Constant-time tag comparison: a datagram/response-timing side channel
+    // on this check would hand an attacker a tag-forgery oracle, which is
+    // exactly what authenticating the datagram is supposed to prevent.
+    if mac.verify(tag.into()).is_err() {
+        return Err(RaknetError::DecryptionFailure);
+    }
+
+    let mut plaintext = ciphertext.to_vec();
+    let mut cipher = ChaCha20::new(key.into(), &nonce.into());
+    cipher.seek(64u32);
+    cipher.apply_keystream(&mut plaintext);
+
+    Ok(plaintext)
+}
+
+/// Reads the per-direction counter `seal` stamped on a datagram, without
+/// verifying it — used by callers layering [`ReplayGuard`] on top of
+/// [`open`].
+pub fn read_counter(datagram: &[u8]) -> Option<u64> {
+    datagram
+        .get(..NONCE_COUNTER_LEN)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+}
+
+/// Rejects a counter this side has already accepted. `open` only checks that
+/// a datagram is authentic under the shared key, not that it's fresh — a
+/// `Reliable*` frame is separately deduplicated by `RecvQueue`'s
+/// `reliable_frame_index`, but `Unreliable`/`UnreliableSequenced` frames have
+/// no such protection, so a captured ciphertext could otherwise be replayed
+/// later and would decrypt and authenticate successfully every time.
+#[derive(Default)]
+pub struct ReplayGuard {
+    highest_seen: Option<u64>,
+    window: std::collections::HashSet<u64>,
+}
+
+impl ReplayGuard {
+    /// Records `counter` as accepted. Returns `true` if it's fresh enough to
+    /// deliver, `false` if it's a duplicate or too far behind the highest
+    /// counter seen so far to still trust.
+    pub fn accept(&mut self, counter: u64) -> bool {
+        if let Some(highest) = self.highest_seen {
+            if counter + REPLAY_WINDOW <= highest {
+                return false;
+            }
+        }
+        if !self.window.insert(counter) {
+            return false;
+        }
+
+        let highest = self.highest_seen.map_or(counter, |h| h.max(counter));
+        self.highest_seen = Some(highest);
+        let floor = highest.saturating_sub(REPLAY_WINDOW);
+        self.window.retain(|c| *c >= floor);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_open_round_trip() {
+        let key = [7u8; 32];
+        let mut send_counter = 0;
+        let sealed = seal(&key, &mut send_counter, b"hello raknet");
+
+        assert_eq!(open(&key, &sealed).unwrap(), b"hello raknet");
+        assert_eq!(send_counter, 1);
+    }
+
+    #[test]
+    fn open_rejects_tampered_datagram() {
+        let key = [7u8; 32];
+        let mut send_counter = 0;
+        let mut sealed = seal(&key, &mut send_counter, b"hello raknet");
+        *sealed.last_mut().unwrap() ^= 1;
+
+        assert_eq!(open(&key, &sealed), Err(RaknetError::DecryptionFailure));
+    }
+
+    #[test]
+    fn open_rejects_wrong_key() {
+        let key = [7u8; 32];
+        let wrong_key = [9u8; 32];
+        let mut send_counter = 0;
+        let sealed = seal(&key, &mut send_counter, b"hello raknet");
+
+        assert_eq!(
+            open(&wrong_key, &sealed),
+            Err(RaknetError::DecryptionFailure)
+        );
+    }
+
+    #[test]
+    fn handshake_derives_matching_keys_on_both_sides() {
+        let a = Handshake::new();
+        let b = Handshake::new();
+        let a_public = a.public_key;
+        let b_public = b.public_key;
+
+        let a_key = a.derive_key(&b_public);
+        let b_key = b.derive_key(&a_public);
+        assert_eq!(a_key, b_key);
+    }
+
+    #[test]
+    fn replay_guard_rejects_duplicate_and_stale_counters() {
+        let mut guard = ReplayGuard::default();
+        assert!(guard.accept(0));
+        assert!(!guard.accept(0), "a repeated counter is a replay");
+
+        for counter in 1..REPLAY_WINDOW * 2 {
+            assert!(guard.accept(counter));
+        }
+        assert!(
+            !guard.accept(0),
+            "a counter far behind the highest seen is too stale to trust"
+        );
+    }
+}
@@ -0,0 +1,24 @@
+//! Small helpers for the odd wire types RakNet uses that don't map cleanly
+//! onto the fixed-width integers in `std`.
+
+use crate::error::{RaknetError, Result};
+
+/// The magic 16-byte sequence carried by every unconnected message,
+/// used to filter out non-RakNet traffic hitting the socket.
+pub const RAKNET_MAGIC: [u8; 16] = [
+    0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34, 0x56, 0x78,
+];
+
+/// Read a little-endian 24-bit unsigned integer (RakNet's sequence/index
+/// number width) from the front of `buf`.
+pub fn read_u24(buf: &[u8]) -> Result<u32> {
+    if buf.len() < 3 {
+        return Err(RaknetError::TooSmallPacketLength);
+    }
+    Ok(buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16)
+}
+
+/// Write `value` as a little-endian 24-bit unsigned integer.
+pub fn write_u24(value: u32) -> [u8; 3] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8]
+}
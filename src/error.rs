@@ -0,0 +1,46 @@
+use std::fmt;
+
+/// Errors that can occur while driving the RakNet protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RaknetError {
+    BindAddressError,
+    ConnectionClosed,
+    NotSupportUnconnectedMessage,
+    IncorrectReply,
+    PacketParseError,
+    PacketSizeExceedMTU,
+    IncorrectReliability,
+    ServerDown,
+    ReadPacketError,
+    TooSmallPacketLength,
+    /// The connection stopped before the encryption handshake derived a
+    /// shared key.
+    EncryptionNotReady,
+    /// A datagram failed AEAD authentication.
+    DecryptionFailure,
+}
+
+impl fmt::Display for RaknetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaknetError::BindAddressError => write!(f, "unable to bind to local address"),
+            RaknetError::ConnectionClosed => write!(f, "connection closed"),
+            RaknetError::NotSupportUnconnectedMessage => {
+                write!(f, "unsupported unconnected message")
+            }
+            RaknetError::IncorrectReply => write!(f, "received an unexpected reply packet"),
+            RaknetError::PacketParseError => write!(f, "failed to parse packet"),
+            RaknetError::PacketSizeExceedMTU => write!(f, "packet size exceeds MTU"),
+            RaknetError::IncorrectReliability => write!(f, "unknown reliability type"),
+            RaknetError::ServerDown => write!(f, "server is down"),
+            RaknetError::ReadPacketError => write!(f, "failed to read packet"),
+            RaknetError::TooSmallPacketLength => write!(f, "packet is too small"),
+            RaknetError::EncryptionNotReady => write!(f, "encryption handshake not complete"),
+            RaknetError::DecryptionFailure => write!(f, "failed to authenticate/decrypt datagram"),
+        }
+    }
+}
+
+impl std::error::Error for RaknetError {}
+
+pub type Result<T> = std::result::Result<T, RaknetError>;
@@ -0,0 +1,53 @@
+//! Reassembly of fragmented reliable frames.
+//!
+//! Frames larger than the connection's MTU are split by the sender into a
+//! compound of sequentially indexed pieces sharing a `compound_id`; this module
+//! buffers the pieces as they arrive and yields the reassembled payload once
+//! every index has been seen.
+
+use std::collections::HashMap;
+
+struct Compound {
+    total: u32,
+    pieces: HashMap<u32, Vec<u8>>,
+}
+
+/// Per-connection fragment reassembly buffer, keyed by `compound_id`.
+#[derive(Default)]
+pub struct FragmentQueue {
+    compounds: HashMap<u16, Compound>,
+}
+
+impl FragmentQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert one fragment of a compound. Returns the reassembled payload
+    /// once `compound_size` distinct indices have been received.
+    pub fn insert(
+        &mut self,
+        compound_id: u16,
+        compound_size: u32,
+        index: u32,
+        data: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        let compound = self.compounds.entry(compound_id).or_insert_with(|| Compound {
+            total: compound_size,
+            pieces: HashMap::new(),
+        });
+
+        compound.pieces.insert(index, data);
+
+        if compound.pieces.len() as u32 != compound.total {
+            return None;
+        }
+
+        let compound = self.compounds.remove(&compound_id).unwrap();
+        let mut out = Vec::new();
+        for i in 0..compound.total {
+            out.extend(compound.pieces.get(&i)?);
+        }
+        Some(out)
+    }
+}
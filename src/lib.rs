@@ -76,10 +76,14 @@ mod fragment;
 mod log;
 mod error;
 mod server;
+mod crypto;
+mod connection;
+mod transport;
 
 pub use crate::arq::Reliability;
 pub use crate::server::*;
 pub use crate::socket::*;
+pub use crate::transport::{DatagramTransport, UdpTransport};
 pub use crate::log::enable_raknet_log;
 
 #[tokio::test]
@@ -110,6 +114,27 @@ async fn test_ping_pong(){
     assert!((0..10).contains(&latency));
 }
 
+#[tokio::test]
+async fn test_ping_motd(){
+    let mut server = RaknetListener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let local_addr = server.local_addr().unwrap();
+    server.listen().await;
+    server.set_motd(packet::Motd{
+        line1: "a rust-raknet test server".to_owned(),
+        online_players: 3,
+        max_players: 20,
+        server_guid: server.guid(),
+        ..packet::Motd::default()
+    }).await;
+
+    let (latency, motd) = socket::RaknetSocket::ping_motd(&local_addr).await.unwrap();
+    assert!((0..10).contains(&latency));
+    assert!(motd.line1 == "a rust-raknet test server");
+    assert!(motd.online_players == 3);
+    assert!(motd.max_players == 20);
+    assert!(motd.server_guid == server.guid());
+}
+
 #[tokio::test]
 async fn test_connect(){
     let mut server = RaknetListener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
@@ -230,147 +255,29 @@ async fn test_send_recv_more_reliability_type_packet(){
 }
 
 #[tokio::test]
-async fn test_loss_packet1(){
-    let notify = std::sync::Arc::new(tokio::sync::Notify::new());
-    let notify2 = notify.clone();
-    let mut server = RaknetListener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
-    let local_addr = server.local_addr().unwrap();
-    server.listen().await;
-    tokio::spawn(async move {
-        let mut client1 = server.accept().await.unwrap();
-        // 80% loss packet rate
-        client1.set_loss_rate(8);
-
-        for i in 0..10{
-            let mut flag = vec![0xfe_u8];
-            let mut data = vec![i as u8; 2000];
-            flag.append(&mut data);
-            client1.send(&flag, Reliability::ReliableOrdered).await.unwrap();
-
-            let data = client1.recv().await.unwrap();
-            assert!(data == flag);
-        }
-        notify.notify_one();
-
-    });
-    let mut client2 = RaknetSocket::connect(&local_addr).await.unwrap();
-    // 80% loss packet rate
-    client2.set_loss_rate(8);
-
-    for i in 0..10{
-        let mut flag = vec![0xfe_u8];
-        let mut data = vec![i as u8; 2000];
-        flag.append(&mut data);
-        client2.send(&flag, Reliability::ReliableOrdered).await.unwrap();
-
-        let data = client2.recv().await.unwrap();
-        assert!(data == flag);
-    }
-    notify2.notified().await;
-}
-
-#[tokio::test]
-async fn test_loss_packet2(){
-    let notify = std::sync::Arc::new(tokio::sync::Notify::new());
-    let notify2 = notify.clone();
+async fn test_connect_encrypted() {
     let mut server = RaknetListener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
     let local_addr = server.local_addr().unwrap();
     server.listen().await;
     tokio::spawn(async move {
         let mut client1 = server.accept().await.unwrap();
-        // 80% loss packet rate
-        client1.set_loss_rate(8);
-
-        for i in 0..10{
-            let mut flag = vec![0xfe_u8];
-            let mut data = vec![i as u8; 2000];
-            flag.append(&mut data);
-            client1.send(&flag, Reliability::ReliableOrdered).await.unwrap();
-        }
-
-        for i in 0..10{
-            let mut flag = vec![0xfe_u8];
-            let mut data = vec![i as u8; 2000];
-            flag.append(&mut data);
-            let data = client1.recv().await.unwrap();
-            assert!(data == flag);
-        }
-        notify.notify_one();
+        let data = client1.recv().await.unwrap();
+        assert!(data == vec![1, 2, 3]);
+        client1.send(&[4, 5, 6], Reliability::Reliable).await.unwrap();
     });
-    let mut client2 = RaknetSocket::connect(&local_addr).await.unwrap();
-    // 80% loss packet rate
-    client2.set_loss_rate(8);
-
-    for i in 0..10{
-        let mut flag = vec![0xfe_u8];
-        let mut data = vec![i as u8; 2000];
-        flag.append(&mut data);
-        client2.send(&flag, Reliability::ReliableOrdered).await.unwrap();
-    }
 
-    for i in 0..10{
-        let mut flag = vec![0xfe_u8];
-        let mut data = vec![i as u8; 2000];
-        flag.append(&mut data);
-        let data = client2.recv().await.unwrap();
-        assert!(data == flag);
-    }
-    notify2.notified().await;
+    let mut client2 = RaknetSocket::connect_encrypted(&local_addr).await.unwrap();
+    assert!(client2.peer_addr().unwrap() == local_addr);
+    client2.send(&[1, 2, 3], Reliability::Reliable).await.unwrap();
+    let buf = client2.recv().await.unwrap();
+    assert!(buf == vec![4, 5, 6]);
 }
 
-#[tokio::test]
-async fn test_loss_packet_with_sequenced(){
-    let notify = std::sync::Arc::new(tokio::sync::Notify::new());
-    let notify2 = notify.clone();
-    let mut server = RaknetListener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
-    let local_addr = server.local_addr().unwrap();
-    server.listen().await;
-    tokio::spawn(async move {
-        let mut client1 = server.accept().await.unwrap();
-        // 80% loss packet rate
-        client1.set_loss_rate(8);
-
-        for i in 0..100{
-            let mut flag = vec![0xfe_u8];
-            let mut data = vec![i as u8; 20];
-            flag.append(&mut data);
-            client1.send(&flag, Reliability::ReliableSequenced).await.unwrap();
-        }
-
-        let mut last = 0;
-        for i in 0..50{
-            let mut flag = vec![0xfe_u8];
-            let mut data = vec![i as u8; 20];
-            flag.append(&mut data);
-            let data = client1.recv().await.unwrap();
-            assert!(data[1] >= last);
-            last = data[1];
-        }
-        notify.notify_one();
-    });
-    let mut client2 = RaknetSocket::connect(&local_addr).await.unwrap();
-    // 80% loss packet rate
-    client2.set_loss_rate(8);
-
-    for i in 0..100{
-        let mut flag = vec![0xfe_u8];
-        let mut data = vec![i as u8; 20];
-        flag.append(&mut data);
-        client2.send(&flag, Reliability::ReliableSequenced).await.unwrap();
-    }
-
-    let mut last = 0;
-    for i in 0..50{
-        let mut flag = vec![0xfe_u8];
-        let mut data = vec![i as u8; 20];
-        flag.append(&mut data);
-        let data = client2.recv().await.unwrap();
-        assert!(data[1] >= last);
-        last = data[1];
-
-    }
-    notify2.notified().await;
-}
+// The old `set_loss_rate`-driven loss-recovery tests that used to live here
+// were randomized and required real sockets; they've been replaced by
+// deterministic tests against the sans-IO `RaknetConnection` state machine
+// directly (see `connection::tests`), which inject loss at fixed indices
+// and drive retransmission with an explicitly-advanced clock instead.
 
 /*
 #[tokio::test]
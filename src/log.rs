@@ -0,0 +1,23 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RAKNET_LOG_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turn on/off the crate's internal debug logging, which is printed to
+/// stdout. Off by default.
+pub fn enable_raknet_log(enable: bool) {
+    RAKNET_LOG_ENABLED.store(enable, Ordering::Relaxed);
+}
+
+pub(crate) fn log_enabled() -> bool {
+    RAKNET_LOG_ENABLED.load(Ordering::Relaxed)
+}
+
+macro_rules! raknet_log_debug {
+    ($($arg:tt)*) => {
+        if crate::log::log_enabled() {
+            println!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use raknet_log_debug;
@@ -0,0 +1,300 @@
+//! RakNet's offline (unconnected) and connection-handshake packets.
+//!
+//! Frame-carried application data is framed by `arq::FrameSet`; this module
+//! only covers the fixed packets exchanged before/while a connection is
+//! being established.
+
+use crate::datatype::RAKNET_MAGIC;
+use crate::error::{RaknetError, Result};
+use crate::utils::cur_timestamp_millis;
+
+/// Packet ID bytes, as they appear as the first byte of a datagram or frame
+/// body.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Id {
+    UnconnectedPing = 0x01,
+    UnconnectedPong = 0x1c,
+    OpenConnectionRequest1 = 0x05,
+    OpenConnectionReply1 = 0x06,
+    OpenConnectionRequest2 = 0x07,
+    OpenConnectionReply2 = 0x08,
+    ConnectionRequest = 0x09,
+    ConnectionRequestAccepted = 0x10,
+    NewIncomingConnection = 0x13,
+    Disconnect = 0x15,
+    /// Post-handshake, pre-data control packet carrying an X25519 ephemeral
+    /// public key so both sides can derive a shared AEAD key.
+    EncryptionHandshake = 0xfe,
+}
+
+pub struct PacketUnconnectedPing {
+    pub time: i64,
+    pub magic: bool,
+    pub guid: u64,
+}
+
+pub struct PacketUnconnectedPong {
+    pub time: i64,
+    pub magic: bool,
+    pub guid: u64,
+    pub motd: String,
+}
+
+fn check_magic(buf: &[u8]) -> bool {
+    buf.len() >= RAKNET_MAGIC.len() && buf[..RAKNET_MAGIC.len()] == RAKNET_MAGIC
+}
+
+/// Parse an `UnconnectedPing` (sent in the clear: the encryption key does
+/// not exist yet at this point in the handshake).
+pub async fn read_packet_ping(buf: &[u8]) -> Result<PacketUnconnectedPing> {
+    if buf.is_empty() || buf[0] != Id::UnconnectedPing as u8 {
+        return Err(RaknetError::PacketParseError);
+    }
+    if buf.len() < 1 + 8 + RAKNET_MAGIC.len() + 8 {
+        return Err(RaknetError::TooSmallPacketLength);
+    }
+
+    let time = i64::from_be_bytes(buf[1..9].try_into().unwrap());
+    let magic = check_magic(&buf[9..]);
+    let guid_offset = 9 + RAKNET_MAGIC.len();
+    let guid = u64::from_be_bytes(buf[guid_offset..guid_offset + 8].try_into().unwrap());
+
+    Ok(PacketUnconnectedPing { time, magic, guid })
+}
+
+/// Serialize an `UnconnectedPong`, always sent in the clear.
+pub async fn write_packet_pong(packet: &PacketUnconnectedPong) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    buf.push(Id::UnconnectedPong as u8);
+    buf.extend_from_slice(&packet.time.to_be_bytes());
+    buf.extend_from_slice(&packet.guid.to_be_bytes());
+    if packet.magic {
+        buf.extend_from_slice(&RAKNET_MAGIC);
+    }
+    buf.extend_from_slice(&(packet.motd.len() as u16).to_be_bytes());
+    buf.extend_from_slice(packet.motd.as_bytes());
+    Ok(buf)
+}
+
+/// Build an `UnconnectedPing` for an outgoing `RaknetSocket::ping`.
+pub async fn write_packet_ping(packet: &PacketUnconnectedPing) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    buf.push(Id::UnconnectedPing as u8);
+    buf.extend_from_slice(&packet.time.to_be_bytes());
+    if packet.magic {
+        buf.extend_from_slice(&RAKNET_MAGIC);
+    }
+    buf.extend_from_slice(&packet.guid.to_be_bytes());
+    Ok(buf)
+}
+
+pub fn new_ping(guid: u64) -> PacketUnconnectedPing {
+    PacketUnconnectedPing {
+        time: cur_timestamp_millis(),
+        magic: true,
+        guid,
+    }
+}
+
+/// The handshake control packet carrying one side's ephemeral X25519 public
+/// key. Always sent as a reliable-ordered frame on channel 0, once, right
+/// after the connection handshake (`ConnectionRequest` /
+/// `ConnectionRequestAccepted`) completes.
+pub struct PacketEncryptionHandshake {
+    pub public_key: [u8; 32],
+}
+
+pub fn write_packet_encryption_handshake(packet: &PacketEncryptionHandshake) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(1 + 32);
+    buf.push(Id::EncryptionHandshake as u8);
+    buf.extend_from_slice(&packet.public_key);
+    buf
+}
+
+pub fn read_packet_encryption_handshake(buf: &[u8]) -> Result<PacketEncryptionHandshake> {
+    if buf.len() != 1 + 32 || buf[0] != Id::EncryptionHandshake as u8 {
+        return Err(RaknetError::PacketParseError);
+    }
+    let mut public_key = [0u8; 32];
+    public_key.copy_from_slice(&buf[1..33]);
+    Ok(PacketEncryptionHandshake { public_key })
+}
+
+/// Sent once by the connecting client, in the clear, to start a session.
+pub fn write_connection_request(guid: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(9);
+    buf.push(Id::ConnectionRequest as u8);
+    buf.extend_from_slice(&guid.to_be_bytes());
+    buf
+}
+
+pub fn read_connection_request(buf: &[u8]) -> Result<u64> {
+    if buf.len() != 9 || buf[0] != Id::ConnectionRequest as u8 {
+        return Err(RaknetError::PacketParseError);
+    }
+    Ok(u64::from_be_bytes(buf[1..9].try_into().unwrap()))
+}
+
+/// Sent once by the listener in reply to a `ConnectionRequest`, carrying
+/// the server's own GUID (the one it also advertises in unconnected pongs).
+pub fn write_connection_request_accepted(server_guid: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(9);
+    buf.push(Id::ConnectionRequestAccepted as u8);
+    buf.extend_from_slice(&server_guid.to_be_bytes());
+    buf
+}
+
+pub fn read_connection_request_accepted(buf: &[u8]) -> Result<u64> {
+    if buf.len() != 9 || buf[0] != Id::ConnectionRequestAccepted as u8 {
+        return Err(RaknetError::PacketParseError);
+    }
+    Ok(u64::from_be_bytes(buf[1..9].try_into().unwrap()))
+}
+
+/// The structured form of the semicolon-delimited `MCPE;...;` string Bedrock
+/// servers put in an `UnconnectedPong`, so callers don't have to split it by
+/// hand. Field order and meaning follow the de-facto Bedrock advertisement
+/// format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Motd {
+    pub edition: String,
+    pub line1: String,
+    pub protocol_version: i32,
+    pub version_name: String,
+    pub online_players: i64,
+    pub max_players: i64,
+    pub server_guid: u64,
+    pub line2: String,
+    pub gamemode: String,
+    pub gamemode_numeric: i32,
+    pub port_v4: u16,
+    pub port_v6: u16,
+}
+
+impl Default for Motd {
+    fn default() -> Self {
+        Self {
+            edition: "MCPE".to_owned(),
+            line1: "rust-raknet".to_owned(),
+            protocol_version: 486,
+            version_name: "1.18.11".to_owned(),
+            online_players: 0,
+            max_players: 10,
+            server_guid: rand::random(),
+            line2: "rust-raknet".to_owned(),
+            gamemode: "Survival".to_owned(),
+            gamemode_numeric: 1,
+            port_v4: 19132,
+            port_v6: 19133,
+        }
+    }
+}
+
+impl std::fmt::Display for Motd {
+    /// Formats the `MCPE;...;` wire string (so `.to_string()` produces it).
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{};{};{};{};{};{};{};{};{};{};{};{};",
+            self.edition,
+            self.line1,
+            self.protocol_version,
+            self.version_name,
+            self.online_players,
+            self.max_players,
+            self.server_guid,
+            self.line2,
+            self.gamemode,
+            self.gamemode_numeric,
+            self.port_v4,
+            self.port_v6
+        )
+    }
+}
+
+impl Motd {
+    /// Parse a `MCPE;...;` string. Trailing fields (from `server_guid` onward)
+    /// are optional, since older/third-party servers often omit them;
+    /// anything missing falls back to `Motd::default()`'s value.
+    pub fn from_string(s: &str) -> Option<Self> {
+        let fields: Vec<&str> = s.trim_end_matches(';').split(';').collect();
+        if fields.len() < 6 {
+            return None;
+        }
+
+        let default = Motd::default();
+        let field = |i: usize| -> Option<&str> { fields.get(i).copied() };
+
+        Some(Self {
+            edition: field(0)?.to_owned(),
+            line1: field(1)?.to_owned(),
+            protocol_version: field(2)?.parse().ok()?,
+            version_name: field(3)?.to_owned(),
+            online_players: field(4)?.parse().ok()?,
+            max_players: field(5)?.parse().ok()?,
+            server_guid: field(6)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.server_guid),
+            line2: field(7).unwrap_or(&default.line2).to_owned(),
+            gamemode: field(8).unwrap_or(&default.gamemode).to_owned(),
+            gamemode_numeric: field(9)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.gamemode_numeric),
+            port_v4: field(10)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.port_v4),
+            port_v6: field(11)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.port_v6),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn motd_round_trips_through_its_wire_string() {
+        let motd = Motd {
+            edition: "MCPE".to_owned(),
+            line1: "a server".to_owned(),
+            protocol_version: 486,
+            version_name: "1.18.11".to_owned(),
+            online_players: 3,
+            max_players: 10,
+            server_guid: 12345,
+            line2: "a world".to_owned(),
+            gamemode: "Survival".to_owned(),
+            gamemode_numeric: 1,
+            port_v4: 19132,
+            port_v6: 19133,
+        };
+
+        let parsed = Motd::from_string(&motd.to_string()).unwrap();
+        assert_eq!(parsed, motd);
+    }
+
+    #[test]
+    fn motd_from_string_fills_in_missing_trailing_fields() {
+        // server_guid isn't checked against Motd::default() here: it's
+        // randomized, so two separately-constructed defaults never match.
+        let default = Motd::default();
+        let s = "MCPE;a server;486;1.18.11;3;10;";
+
+        let parsed = Motd::from_string(s).unwrap();
+        assert_eq!(parsed.edition, "MCPE");
+        assert_eq!(parsed.online_players, 3);
+        assert_eq!(parsed.max_players, 10);
+        assert_eq!(parsed.line2, default.line2);
+        assert_eq!(parsed.gamemode, default.gamemode);
+        assert_eq!(parsed.port_v4, default.port_v4);
+        assert_eq!(parsed.port_v6, default.port_v6);
+    }
+
+    #[test]
+    fn motd_from_string_rejects_too_few_fields() {
+        assert!(Motd::from_string("MCPE;a server;486").is_none());
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::connection::peek_migration_proof;
+use crate::error::{RaknetError, Result};
+use crate::packet::{self, Id, Motd, PacketUnconnectedPong};
+use crate::socket::{MigrateHook, RaknetSocket};
+use crate::transport::{DatagramTransport, UdpTransport};
+use crate::utils::cur_timestamp_millis;
+
+/// How long a connection may go without a single datagram arriving before
+/// the accept loop forgets it. Bounds `connections`/`by_guid`/the migration
+/// guard's memory for a long-running listener whose clients keep
+/// reconnecting or migrating instead of cleanly disconnecting.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// How often the accept loop sweeps for connections past `IDLE_TIMEOUT`.
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Per-connection bookkeeping the accept loop keeps around so it can
+/// recognize a peer that migrated to a new `SocketAddr` (see
+/// [`RaknetListener::listen`]) and keep routing its datagrams to the same
+/// [`RaknetSocket`] instead of tearing the connection down.
+struct ConnectionEntry {
+    inbound: mpsc::UnboundedSender<Vec<u8>>,
+    peer_addr: Arc<StdMutex<SocketAddr>>,
+    migrate_hook: MigrateHook,
+    encryption: Arc<Mutex<Option<[u8; 32]>>>,
+    guid: u64,
+    last_seen: Instant,
+}
+
+/// Tracks reliable-frame indices already consumed to authorize a migration,
+/// per GUID: guards against replaying a captured cleartext packet to hijack
+/// a connection a second time.
+#[derive(Default)]
+struct MigrationGuard {
+    seen: HashMap<u64, HashSet<u32>>,
+}
+
+impl MigrationGuard {
+    /// Records `reliable_index` as having authorized a migration for `guid`.
+    /// Returns `true` the first time this pair is seen, `false` on replay.
+    fn observe(&mut self, guid: u64, reliable_index: u32) -> bool {
+        self.seen.entry(guid).or_default().insert(reliable_index)
+    }
+
+    /// Forgets everything tracked for `guid`, once its connection is gone.
+    fn forget(&mut self, guid: u64) {
+        self.seen.remove(&guid);
+    }
+}
+
+/// Removes `addr`'s connection, along with its `by_guid` entry and any
+/// tracked migration state, so a disconnected or reaped peer doesn't linger
+/// in any of the three maps.
+fn forget_connection(
+    addr: SocketAddr,
+    connections: &mut HashMap<SocketAddr, ConnectionEntry>,
+    by_guid: &mut HashMap<u64, SocketAddr>,
+    migration_guard: &mut MigrationGuard,
+) {
+    if let Some(entry) = connections.remove(&addr) {
+        by_guid.remove(&entry.guid);
+        migration_guard.forget(entry.guid);
+    }
+}
+
+/// Accepts incoming RakNet connections on a single, shared
+/// [`DatagramTransport`] (plain UDP by default), generic for the same
+/// reason [`RaknetSocket`] is.
+pub struct RaknetListener<T: DatagramTransport = UdpTransport> {
+    transport: Arc<T>,
+    local_addr: SocketAddr,
+    guid: u64,
+    motd: Arc<Mutex<String>>,
+    accept_rx: Option<mpsc::UnboundedReceiver<RaknetSocket<T>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl RaknetListener<UdpTransport> {
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        let transport = UdpTransport::bind(addr)
+            .await
+            .map_err(|_| RaknetError::BindAddressError)?;
+        Self::bind_with_transport(transport).await
+    }
+}
+
+impl<T: DatagramTransport> RaknetListener<T> {
+    /// Accept connections over an already-bound `transport`, e.g. one that
+    /// tunnels RakNet datagrams through a WebSocket relay instead of a
+    /// directly reachable UDP port.
+    pub async fn bind_with_transport(transport: T) -> Result<Self> {
+        let local_addr = transport
+            .local_addr()
+            .map_err(|_| RaknetError::BindAddressError)?;
+        let guid = rand::random();
+        Ok(Self {
+            transport: Arc::new(transport),
+            local_addr,
+            guid,
+            motd: Arc::new(Mutex::new(
+                Motd {
+                    server_guid: guid,
+                    port_v4: local_addr.port(),
+                    port_v6: local_addr.port(),
+                    ..Motd::default()
+                }
+                .to_string(),
+            )),
+            accept_rx: None,
+            task: None,
+        })
+    }
+
+    /// Set the MOTD a client sees when it pings this server. `motd.server_guid`
+    /// is under the caller's control like every other field — use
+    /// [`Self::guid`] to keep it aligned with the GUID this listener actually
+    /// advertises in the connect handshake and unconnected pongs.
+    pub async fn set_motd(&self, motd: Motd) {
+        *self.motd.lock().await = motd.to_string();
+    }
+
+    /// Set the raw MOTD string returned verbatim in unconnected pongs,
+    /// bypassing the [`Motd`] struct for servers with a non-standard format.
+    pub async fn set_full_motd(&self, motd: String) {
+        *self.motd.lock().await = motd;
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    /// This listener's GUID: the value sent in the connect handshake's
+    /// `ConnectionRequestAccepted` and in every `UnconnectedPong`.
+    pub fn guid(&self) -> u64 {
+        self.guid
+    }
+
+    /// Start the background accept loop. Must be called before `accept`.
+    pub async fn listen(&mut self) {
+        let transport = self.transport.clone();
+        let guid = self.guid;
+        let motd = self.motd.clone();
+        let (accept_tx, accept_rx) = mpsc::unbounded_channel();
+        self.accept_rx = Some(accept_rx);
+        let local_addr = self.local_addr;
+
+        let task = tokio::spawn(async move {
+            let mut connections: HashMap<SocketAddr, ConnectionEntry> = HashMap::new();
+            let mut by_guid: HashMap<u64, SocketAddr> = HashMap::new();
+            let mut migration_guard = MigrationGuard::default();
+            let mut buf = vec![0u8; 2048];
+            let mut reap_interval = tokio::time::interval(REAP_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    result = transport.recv_from(&mut buf) => {
+                        let (size, peer_addr) = match result {
+                            Ok(v) => v,
+                            Err(_) => return,
+                        };
+                        let datagram = &buf[..size];
+
+                        if let Some(entry) = connections.get_mut(&peer_addr) {
+                            entry.last_seen = Instant::now();
+                            if entry.inbound.send(datagram.to_vec()).is_ok() {
+                                continue;
+                            }
+                            forget_connection(peer_addr, &mut connections, &mut by_guid, &mut migration_guard);
+                        }
+
+                        // Not from a known address: see if it's a known GUID
+                        // reappearing from a new one (NAT rebinding, Wi-Fi/cell
+                        // handoff, etc.) before falling through to the unconnected
+                        // handlers below. Only migrate once the datagram has proven
+                        // it belongs to that connection with a *fresh* reliable
+                        // frame — either a reliable frameset carrying the GUID in
+                        // the clear, or (if the connection is encrypted) one that
+                        // decrypts and authenticates under that connection's key —
+                        // so neither a spoofed source address nor a replayed
+                        // capture of an earlier packet can hijack it.
+                        if let Some((guid, old_addr, reliable_index)) =
+                            find_migrated_peer(datagram, &by_guid, &connections).await
+                        {
+                            if migration_guard.observe(guid, reliable_index) {
+                                if let Some(mut entry) = connections.remove(&old_addr) {
+                                    *entry.peer_addr.lock().unwrap() = peer_addr;
+                                    entry.last_seen = Instant::now();
+                                    by_guid.insert(guid, peer_addr);
+                                    if let Some(hook) = entry.migrate_hook.lock().unwrap().as_mut() {
+                                        hook(peer_addr);
+                                    }
+                                    let _ = entry.inbound.send(datagram.to_vec());
+                                    connections.insert(peer_addr, entry);
+                                    continue;
+                                }
+                            }
+                        }
+
+                        match datagram.first().copied() {
+                            Some(id) if id == Id::UnconnectedPing as u8 => {
+                                if packet::read_packet_ping(datagram).await.is_err() {
+                                    continue;
+                                }
+                                let pong = PacketUnconnectedPong {
+                                    time: cur_timestamp_millis(),
+                                    magic: true,
+                                    guid,
+                                    motd: motd.lock().await.clone(),
+                                };
+                                if let Ok(reply) = packet::write_packet_pong(&pong).await {
+                                    let _ = transport.send_to(&reply, peer_addr).await;
+                                }
+                            }
+                            Some(id) if id == Id::ConnectionRequest as u8 => {
+                                let client_guid = match packet::read_connection_request(datagram) {
+                                    Ok(g) => g,
+                                    Err(_) => continue,
+                                };
+                                let reply = packet::write_connection_request_accepted(guid);
+                                if transport.send_to(&reply, peer_addr).await.is_err() {
+                                    continue;
+                                }
+
+                                let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+                                let shared_peer_addr = Arc::new(StdMutex::new(peer_addr));
+                                let migrate_hook: MigrateHook = Arc::new(StdMutex::new(None));
+
+                                let socket = RaknetSocket::from_parts(
+                                    shared_peer_addr.clone(),
+                                    local_addr,
+                                    client_guid,
+                                    transport.clone(),
+                                    inbound_rx,
+                                    migrate_hook.clone(),
+                                    false,
+                                    None,
+                                );
+                                // A reconnect from the same GUID replaces
+                                // whatever this listener still had on file for
+                                // it, rather than leaking the old entry.
+                                forget_connection(peer_addr, &mut connections, &mut by_guid, &mut migration_guard);
+                                if let Some(&stale_addr) = by_guid.get(&client_guid) {
+                                    forget_connection(stale_addr, &mut connections, &mut by_guid, &mut migration_guard);
+                                }
+                                connections.insert(
+                                    peer_addr,
+                                    ConnectionEntry {
+                                        inbound: inbound_tx,
+                                        peer_addr: shared_peer_addr,
+                                        migrate_hook,
+                                        encryption: socket.is_encrypted(),
+                                        guid: client_guid,
+                                        last_seen: Instant::now(),
+                                    },
+                                );
+                                by_guid.insert(client_guid, peer_addr);
+
+                                if accept_tx.send(socket).is_err() {
+                                    return;
+                                }
+                            }
+                            _ => {
+                                // Unrecognized datagram from an address with no
+                                // active connection; drop it.
+                            }
+                        }
+                    }
+                    _ = reap_interval.tick() => {
+                        let now = Instant::now();
+                        let idle: Vec<SocketAddr> = connections
+                            .iter()
+                            .filter(|(_, entry)| now.duration_since(entry.last_seen) > IDLE_TIMEOUT)
+                            .map(|(addr, _)| *addr)
+                            .collect();
+                        for addr in idle {
+                            forget_connection(addr, &mut connections, &mut by_guid, &mut migration_guard);
+                        }
+                    }
+                }
+            }
+        });
+
+        self.task = Some(task);
+    }
+
+    /// Wait for the next accepted connection.
+    pub async fn accept(&mut self) -> Result<RaknetSocket<T>> {
+        self.accept_rx
+            .as_mut()
+            .ok_or(RaknetError::ServerDown)?
+            .recv()
+            .await
+            .ok_or(RaknetError::ServerDown)
+    }
+}
+
+/// If `datagram` proves it came from a connection we already know (by GUID)
+/// via a genuine reliable frame, return that GUID, the connection's current
+/// `SocketAddr`, and the frame's reliable index (for the caller's replay
+/// check). Cleartext connections are recognized by parsing the frameset's
+/// GUID field directly; encrypted ones additionally require the datagram to
+/// decrypt and authenticate under that connection's key, since the GUID
+/// isn't visible without it. A datagram whose frame isn't reliable proves
+/// nothing, since unreliable/sequenced frames can't be checked for replay,
+/// and is never treated as migration evidence.
+async fn find_migrated_peer(
+    datagram: &[u8],
+    by_guid: &HashMap<u64, SocketAddr>,
+    connections: &HashMap<SocketAddr, ConnectionEntry>,
+) -> Option<(u64, SocketAddr, u32)> {
+    if let Some(proof) = peek_migration_proof(datagram) {
+        if let (Some(&addr), Some(index)) = (
+            by_guid.get(&proof.sender_guid),
+            proof.reliable_frame_index,
+        ) {
+            let encrypted = match connections.get(&addr) {
+                Some(entry) => entry.encryption.lock().await.is_some(),
+                None => false,
+            };
+            if !encrypted {
+                return Some((proof.sender_guid, addr, index));
+            }
+        }
+    }
+
+    for (addr, entry) in connections {
+        let key = entry.encryption.lock().await.clone();
+        if let Some(key) = key {
+            if let Ok(plaintext) = crate::crypto::open(&key, datagram) {
+                if let Some(proof) = peek_migration_proof(&plaintext) {
+                    if by_guid.get(&proof.sender_guid) == Some(addr) {
+                        if let Some(index) = proof.reliable_frame_index {
+                            return Some((proof.sender_guid, *addr, index));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arq::Reliability;
+    use crate::connection::RaknetConnection;
+    use std::time::Instant as StdInstant;
+    use tokio::sync::mpsc;
+
+    fn connection_entry(guid: u64) -> ConnectionEntry {
+        ConnectionEntry {
+            inbound: mpsc::unbounded_channel().0,
+            peer_addr: Arc::new(StdMutex::new("127.0.0.1:1".parse().unwrap())),
+            migrate_hook: Arc::new(StdMutex::new(None)),
+            encryption: Arc::new(Mutex::new(None)),
+            guid,
+            last_seen: Instant::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn find_migrated_peer_recognizes_a_fresh_reliable_frame_from_a_known_guid() {
+        let old_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut conn = RaknetConnection::new(42);
+        conn.push_send(StdInstant::now(), vec![1, 2, 3], Reliability::ReliableOrdered);
+        let datagram = conn.poll_transmit().unwrap();
+
+        let mut by_guid = HashMap::new();
+        by_guid.insert(42, old_addr);
+        let mut connections = HashMap::new();
+        connections.insert(old_addr, connection_entry(42));
+
+        let (guid, addr, _reliable_index) = find_migrated_peer(&datagram, &by_guid, &connections)
+            .await
+            .unwrap();
+        assert_eq!(guid, 42);
+        assert_eq!(addr, old_addr);
+    }
+
+    #[tokio::test]
+    async fn find_migrated_peer_ignores_an_unknown_guid() {
+        let mut conn = RaknetConnection::new(42);
+        conn.push_send(StdInstant::now(), vec![1, 2, 3], Reliability::ReliableOrdered);
+        let datagram = conn.poll_transmit().unwrap();
+
+        let by_guid = HashMap::new();
+        let connections = HashMap::new();
+        assert!(find_migrated_peer(&datagram, &by_guid, &connections)
+            .await
+            .is_none());
+    }
+
+    #[test]
+    fn migration_guard_rejects_a_replayed_index_but_accepts_a_new_one() {
+        let mut guard = MigrationGuard::default();
+        assert!(guard.observe(42, 0));
+        assert!(!guard.observe(42, 0));
+        assert!(guard.observe(42, 1));
+    }
+
+    #[test]
+    fn migration_guard_forget_clears_tracked_indices_for_a_guid() {
+        let mut guard = MigrationGuard::default();
+        assert!(guard.observe(42, 0));
+        guard.forget(42);
+        assert!(guard.observe(42, 0));
+    }
+
+    #[test]
+    fn forget_connection_removes_from_every_map() {
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut connections = HashMap::new();
+        connections.insert(addr, connection_entry(42));
+        let mut by_guid = HashMap::new();
+        by_guid.insert(42, addr);
+        let mut migration_guard = MigrationGuard::default();
+        migration_guard.observe(42, 0);
+
+        forget_connection(addr, &mut connections, &mut by_guid, &mut migration_guard);
+
+        assert!(!connections.contains_key(&addr));
+        assert!(!by_guid.contains_key(&42));
+        assert!(migration_guard.observe(42, 0));
+    }
+}
@@ -0,0 +1,513 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::arq::Reliability;
+use crate::connection::RaknetConnection;
+use crate::crypto::{self, Handshake};
+use crate::error::{RaknetError, Result};
+use crate::log::raknet_log_debug;
+use crate::packet::{
+    self, read_packet_encryption_handshake, write_packet_encryption_handshake, Id,
+    PacketEncryptionHandshake, PacketUnconnectedPong,
+};
+use crate::transport::{DatagramTransport, UdpTransport};
+use crate::utils::cur_timestamp_millis;
+
+/// One established RakNet connection, generic over the [`DatagramTransport`]
+/// its datagrams actually travel over (plain UDP by default). Created
+/// either by [`RaknetSocket::connect`] (client side) or by
+/// [`crate::RaknetListener::accept`] (server side, where it shares the
+/// listener's underlying transport).
+pub struct RaknetSocket<T: DatagramTransport = UdpTransport> {
+    peer_addr: Arc<StdMutex<SocketAddr>>,
+    local_addr: SocketAddr,
+    guid: u64,
+    user_recv_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    user_send_tx: mpsc::UnboundedSender<(Vec<u8>, Reliability)>,
+    loss_rate: Arc<AtomicU8>,
+    encryption: Arc<Mutex<Option<[u8; 32]>>>,
+    migrate_hook: MigrateHook,
+    _task: JoinHandle<()>,
+    _transport: std::marker::PhantomData<T>,
+}
+
+/// Callback registered through [`RaknetSocket::on_migrate`], invoked with
+/// the connection's new [`SocketAddr`] whenever [`crate::RaknetListener`]
+/// observes it change.
+pub(crate) type MigrateHook = Arc<StdMutex<Option<Box<dyn FnMut(SocketAddr) + Send>>>>;
+
+/// Thin async wrapper around a sans-IO [`RaknetConnection`]: owns the actual
+/// transport, applies the loss-rate knob used by tests, and performs AEAD
+/// encrypt/decrypt on whatever the connection hands it to/from the wire.
+/// All reliability/ordering/fragmentation logic itself lives in
+/// `RaknetConnection`, not here.
+struct Worker<T: DatagramTransport> {
+    transport: Arc<T>,
+    peer_addr: Arc<StdMutex<SocketAddr>>,
+    connection: RaknetConnection,
+    loss_rate: Arc<AtomicU8>,
+    encryption: Arc<Mutex<Option<[u8; 32]>>>,
+    send_counter: u64,
+    replay_guard: crypto::ReplayGuard,
+}
+
+impl<T: DatagramTransport> Worker<T> {
+    async fn transmit_all(&mut self) {
+        while let Some(datagram) = self.connection.poll_transmit() {
+            let key = self.encryption.lock().await.clone();
+            let encrypted = match key {
+                // `send_counter` lives on the Worker so retransmits of the
+                // same frameset still get a fresh nonce; only the derived
+                // key itself is shared with the decrypt side.
+                Some(key) => crypto::seal(&key, &mut self.send_counter, &datagram),
+                None => datagram,
+            };
+            // Re-read the peer address on every datagram: a migration may
+            // have updated it since the last send.
+            let peer_addr = *self.peer_addr.lock().unwrap();
+            let _ = self.transport.send_to(&encrypted, peer_addr).await;
+        }
+    }
+
+    async fn send(&mut self, data: Vec<u8>, reliability: Reliability) {
+        self.connection.push_send(Instant::now(), data, reliability);
+        self.transmit_all().await;
+    }
+
+    /// Feeds one already-decrypted inbound datagram to the connection
+    /// state machine and drains the application payloads it completed.
+    fn on_datagram(&mut self, datagram: &[u8]) -> Vec<Vec<u8>> {
+        let loss_rate = self.loss_rate.load(Ordering::Relaxed);
+        if loss_rate > 0 && (rand_u8() % 10) < loss_rate {
+            return Vec::new();
+        }
+
+        self.connection.handle_datagram(Instant::now(), datagram);
+        std::iter::from_fn(|| self.connection.poll_recv()).collect()
+    }
+
+    async fn handle_timeout(&mut self) {
+        self.connection.handle_timeout(Instant::now());
+        self.transmit_all().await;
+    }
+}
+
+fn rand_u8() -> u8 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    (SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos()
+        % 256) as u8
+}
+
+impl<T: DatagramTransport> RaknetSocket<T> {
+    /// Build a connection around an already-accepted/established peer.
+    /// `peer_addr`/`migrate_hook` are shared with [`crate::RaknetListener`]
+    /// on the server side so it can update the live address and fire the
+    /// migration callback when a known GUID reappears from a new
+    /// `SocketAddr`; the client path ([`Self::connect_with_transport_inner`])
+    /// just creates its own since nothing else needs to observe it.
+    /// `initiate_handshake` makes the worker task send the first encryption
+    /// handshake frame over the connection's reliable path as soon as it
+    /// starts; either way, the task also answers a handshake frame it didn't
+    /// initiate, so a server-side connection responds to a client that
+    /// requests encryption without having to know upfront that it will.
+    /// `handshake_done`, if given, fires once this side has derived the
+    /// shared key (only meaningful together with `initiate_handshake`).
+    pub(crate) fn from_parts(
+        peer_addr: Arc<StdMutex<SocketAddr>>,
+        local_addr: SocketAddr,
+        guid: u64,
+        transport: Arc<T>,
+        mut inbound_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+        migrate_hook: MigrateHook,
+        initiate_handshake: bool,
+        handshake_done: Option<oneshot::Sender<()>>,
+    ) -> Self {
+        let (user_recv_tx, user_recv_rx) = mpsc::unbounded_channel();
+        let (user_send_tx, mut user_send_rx) = mpsc::unbounded_channel::<(Vec<u8>, Reliability)>();
+        let loss_rate = Arc::new(AtomicU8::new(0));
+        let encryption = Arc::new(Mutex::new(None));
+
+        let mut worker = Worker {
+            transport: transport.clone(),
+            peer_addr: peer_addr.clone(),
+            connection: RaknetConnection::new(guid),
+            loss_rate: loss_rate.clone(),
+            encryption: encryption.clone(),
+            send_counter: 0,
+            replay_guard: crypto::ReplayGuard::default(),
+        };
+
+        let task = tokio::spawn(async move {
+            let mut handshake_done = handshake_done;
+            let mut pending_handshake = if initiate_handshake {
+                let handshake = Handshake::new();
+                let frame = write_packet_encryption_handshake(&PacketEncryptionHandshake {
+                    public_key: handshake.public_key,
+                });
+                worker
+                    .connection
+                    .push_send(Instant::now(), frame, Reliability::ReliableOrdered);
+                worker.transmit_all().await;
+                Some(handshake)
+            } else {
+                None
+            };
+
+            let mut resend_deadline = worker.connection.poll_timeout();
+            loop {
+                // Re-armed every iteration from the connection's own view of
+                // when its next unacked frameset is due: no timer fires at
+                // all once everything outstanding has been acked, instead of
+                // ticking on a flat interval regardless of whether there's
+                // anything to retransmit.
+                let sleep = tokio::time::sleep_until(
+                    resend_deadline
+                        .map(tokio::time::Instant::from_std)
+                        .unwrap_or_else(tokio::time::Instant::now),
+                );
+                tokio::pin!(sleep);
+
+                tokio::select! {
+                    Some(datagram) = inbound_rx.recv() => {
+                        let plaintext = {
+                            let key = worker.encryption.lock().await;
+                            match &*key {
+                                Some(k) => match crypto::open(k, &datagram) {
+                                    Ok(p) => {
+                                        // Reliable* frames are deduplicated by
+                                        // RecvQueue's reliable_frame_index, but
+                                        // Unreliable/UnreliableSequenced ones
+                                        // aren't, so a replayed ciphertext would
+                                        // otherwise decrypt and redeliver here.
+                                        let counter = crypto::read_counter(&datagram).unwrap_or(0);
+                                        if !worker.replay_guard.accept(counter) {
+                                            continue;
+                                        }
+                                        p
+                                    }
+                                    // Tag mismatch: drop silently rather than
+                                    // let a tampered datagram reach the parser.
+                                    Err(_) => continue,
+                                },
+                                None => datagram,
+                            }
+                        };
+
+                        for payload in worker.on_datagram(&plaintext) {
+                            if payload.first() == Some(&(Id::EncryptionHandshake as u8)) {
+                                if let Ok(incoming) = read_packet_encryption_handshake(&payload) {
+                                    if let Some(handshake) = pending_handshake.take() {
+                                        // We initiated; this is the peer's reply.
+                                        let key = handshake.derive_key(&incoming.public_key);
+                                        *worker.encryption.lock().await = Some(key);
+                                        raknet_log_debug!("encryption handshake complete");
+                                        if let Some(tx) = handshake_done.take() {
+                                            let _ = tx.send(());
+                                        }
+                                    } else {
+                                        // The peer initiated: answer with our
+                                        // own ephemeral key and derive the
+                                        // shared secret immediately.
+                                        let ours = Handshake::new();
+                                        let reply = write_packet_encryption_handshake(
+                                            &PacketEncryptionHandshake {
+                                                public_key: ours.public_key,
+                                            },
+                                        );
+                                        worker.connection.push_send(
+                                            Instant::now(),
+                                            reply,
+                                            Reliability::ReliableOrdered,
+                                        );
+                                        worker.transmit_all().await;
+                                        let key = ours.derive_key(&incoming.public_key);
+                                        *worker.encryption.lock().await = Some(key);
+                                        raknet_log_debug!("encryption handshake complete");
+                                    }
+                                    continue;
+                                }
+                            }
+                            if user_recv_tx.send(payload).is_err() {
+                                return;
+                            }
+                        }
+                        // Send any acks the datagram queued right away.
+                        worker.transmit_all().await;
+                    }
+                    Some((data, reliability)) = user_send_rx.recv() => {
+                        worker.send(data, reliability).await;
+                    }
+                    _ = &mut sleep, if resend_deadline.is_some() => {
+                        worker.handle_timeout().await;
+                    }
+                    else => return,
+                }
+                resend_deadline = worker.connection.poll_timeout();
+            }
+        });
+
+        Self {
+            peer_addr,
+            local_addr,
+            guid,
+            user_recv_rx,
+            user_send_tx,
+            loss_rate,
+            encryption,
+            migrate_hook,
+            _task: task,
+            _transport: std::marker::PhantomData,
+        }
+    }
+
+    /// Connect to a RakNet server reachable through `transport`, without
+    /// negotiating transport encryption. `transport` must already be able
+    /// to exchange datagrams with `peer_addr` (bound, and for relay-style
+    /// transports, rendezvoused).
+    pub async fn connect_with_transport(transport: T, peer_addr: SocketAddr) -> Result<Self> {
+        Self::connect_with_transport_inner(transport, peer_addr, false).await
+    }
+
+    /// Like [`Self::connect_with_transport`], additionally performing the
+    /// X25519 handshake so all subsequent datagrams are encrypted and
+    /// authenticated with ChaCha20-Poly1305.
+    pub async fn connect_encrypted_with_transport(
+        transport: T,
+        peer_addr: SocketAddr,
+    ) -> Result<Self> {
+        Self::connect_with_transport_inner(transport, peer_addr, true).await
+    }
+
+    async fn connect_with_transport_inner(
+        transport: T,
+        peer_addr: SocketAddr,
+        encrypted: bool,
+    ) -> Result<Self> {
+        let local_addr = transport
+            .local_addr()
+            .map_err(|_| RaknetError::BindAddressError)?;
+        let transport = Arc::new(transport);
+        let guid: u64 = rand_guid();
+
+        transport
+            .send_to(&packet::write_connection_request(guid), peer_addr)
+            .await
+            .map_err(|_| RaknetError::BindAddressError)?;
+        let buf = recv_from_peer(transport.as_ref(), peer_addr, 64)
+            .await
+            .ok_or(RaknetError::ServerDown)?;
+        packet::read_connection_request_accepted(&buf)?;
+
+        let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+        let transport_clone = transport.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 2048];
+            loop {
+                match transport_clone.recv_from(&mut buf).await {
+                    Ok((n, from)) if from == peer_addr => {
+                        if inbound_tx.send(buf[..n].to_vec()).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => continue,
+                    Err(_) => return,
+                }
+            }
+        });
+
+        let (handshake_tx, handshake_rx) = oneshot::channel();
+        let socket = Self::from_parts(
+            Arc::new(StdMutex::new(peer_addr)),
+            local_addr,
+            guid,
+            transport,
+            inbound_rx,
+            Arc::new(StdMutex::new(None)),
+            encrypted,
+            encrypted.then_some(handshake_tx),
+        );
+
+        if encrypted {
+            // Wait for the worker task to drive the handshake over the
+            // connection's reliable path and derive the shared key, so
+            // callers of `connect_encrypted*` only ever see an already-ready
+            // socket.
+            handshake_rx
+                .await
+                .map_err(|_| RaknetError::EncryptionNotReady)?;
+        }
+
+        Ok(socket)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(*self.peer_addr.lock().unwrap())
+    }
+
+    /// Register a callback fired whenever this connection migrates to a new
+    /// `SocketAddr` (see connection migration in [`crate::RaknetListener`]).
+    /// Only one callback is kept; registering again replaces it.
+    pub fn on_migrate<F: FnMut(SocketAddr) + Send + 'static>(&self, callback: F) {
+        *self.migrate_hook.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    pub fn guid(&self) -> u64 {
+        self.guid
+    }
+
+    pub async fn send(&self, buf: &[u8], reliability: Reliability) -> Result<()> {
+        self.user_send_tx
+            .send((buf.to_vec(), reliability))
+            .map_err(|_| RaknetError::ConnectionClosed)
+    }
+
+    pub async fn recv(&mut self) -> Result<Vec<u8>> {
+        self.user_recv_rx
+            .recv()
+            .await
+            .ok_or(RaknetError::ConnectionClosed)
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.user_send_tx
+            .send((vec![Id::Disconnect as u8], Reliability::Reliable))
+            .ok();
+        Ok(())
+    }
+
+    /// Artificially drop a fraction of inbound datagrams for loss-recovery
+    /// testing: about `rate` out of every 10 datagrams are dropped, chosen
+    /// pseudo-randomly from the system clock.
+    pub fn set_loss_rate(&self, rate: u8) {
+        self.loss_rate.store(rate, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_encrypted(&self) -> Arc<Mutex<Option<[u8; 32]>>> {
+        self.encryption.clone()
+    }
+}
+
+impl RaknetSocket<UdpTransport> {
+    /// Connect to a RakNet server over plain UDP, without negotiating
+    /// transport encryption.
+    pub async fn connect(addr: &SocketAddr) -> Result<Self> {
+        let transport = UdpTransport::bind("0.0.0.0:0".parse().unwrap())
+            .await
+            .map_err(|_| RaknetError::BindAddressError)?;
+        Self::connect_with_transport(transport, *addr).await
+    }
+
+    /// Connect to a RakNet server over plain UDP and additionally perform
+    /// the X25519 handshake so all subsequent datagrams are encrypted and
+    /// authenticated with ChaCha20-Poly1305.
+    pub async fn connect_encrypted(addr: &SocketAddr) -> Result<Self> {
+        let transport = UdpTransport::bind("0.0.0.0:0".parse().unwrap())
+            .await
+            .map_err(|_| RaknetError::BindAddressError)?;
+        Self::connect_encrypted_with_transport(transport, *addr).await
+    }
+
+    /// One-off unconnected ping: measures round-trip latency to `addr`
+    /// without establishing a connection. Always sent in cleartext.
+    pub async fn ping(addr: &SocketAddr) -> Result<i64> {
+        let udp = UdpSocket::bind("0.0.0.0:0")
+            .await
+            .map_err(|_| RaknetError::BindAddressError)?;
+        let (latency, _) = Self::ping_inner(&udp, addr).await?;
+        Ok(latency)
+    }
+
+    /// Like [`RaknetSocket::ping`], but also parses the server's advertised
+    /// [`crate::packet::Motd`] out of the pong so callers can read its
+    /// player counts and version before connecting.
+    pub async fn ping_motd(addr: &SocketAddr) -> Result<(i64, crate::packet::Motd)> {
+        let udp = UdpSocket::bind("0.0.0.0:0")
+            .await
+            .map_err(|_| RaknetError::BindAddressError)?;
+        let (latency, motd) = Self::ping_inner(&udp, addr).await?;
+        let motd = crate::packet::Motd::from_string(&motd).ok_or(RaknetError::PacketParseError)?;
+        Ok((latency, motd))
+    }
+
+    async fn ping_inner(udp: &UdpSocket, addr: &SocketAddr) -> Result<(i64, String)> {
+        let ping = packet::new_ping(rand_guid());
+        let sent_at = ping.time;
+        let buf = packet::write_packet_ping(&ping).await?;
+        udp.send_to(&buf, addr)
+            .await
+            .map_err(|_| RaknetError::BindAddressError)?;
+
+        let mut recv_buf = vec![0u8; 2048];
+        let (size, _) = udp
+            .recv_from(&mut recv_buf)
+            .await
+            .map_err(|_| RaknetError::ServerDown)?;
+
+        if recv_buf.first() != Some(&(Id::UnconnectedPong as u8)) {
+            return Err(RaknetError::IncorrectReply);
+        }
+
+        let pong = parse_pong(&recv_buf[..size])?;
+        Ok((cur_timestamp_millis() - sent_at, pong.motd))
+    }
+}
+
+/// Reads datagrams from `transport` until one arrives from `peer_addr`
+/// (or the transport errors out), used by the handshake steps that predate
+/// the per-connection inbound-forwarding task.
+async fn recv_from_peer<T: DatagramTransport>(
+    transport: &T,
+    peer_addr: SocketAddr,
+    max_len: usize,
+) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; max_len];
+    loop {
+        let (size, from) = transport.recv_from(&mut buf).await.ok()?;
+        if from == peer_addr {
+            return Some(buf[..size].to_vec());
+        }
+    }
+}
+
+fn parse_pong(buf: &[u8]) -> Result<PacketUnconnectedPong> {
+    if buf.len() < 1 + 8 + 8 {
+        return Err(RaknetError::TooSmallPacketLength);
+    }
+    let time = i64::from_be_bytes(buf[1..9].try_into().unwrap());
+    let guid = u64::from_be_bytes(buf[9..17].try_into().unwrap());
+    let mut offset = 17;
+    let magic = buf.len() >= offset + 16 && &buf[offset..offset + 16] == &crate::datatype::RAKNET_MAGIC[..];
+    if magic {
+        offset += 16;
+    }
+    let motd = if buf.len() >= offset + 2 {
+        let len = u16::from_be_bytes(buf[offset..offset + 2].try_into().unwrap()) as usize;
+        offset += 2;
+        String::from_utf8_lossy(&buf[offset..(offset + len).min(buf.len())]).to_string()
+    } else {
+        String::new()
+    };
+    Ok(PacketUnconnectedPong {
+        time,
+        magic,
+        guid,
+        motd,
+    })
+}
+
+fn rand_guid() -> u64 {
+    rand::random()
+}
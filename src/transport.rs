@@ -0,0 +1,159 @@
+//! Abstraction over "something datagrams can be sent through", so the RakNet
+//! reliability stack isn't hard-wired to a UDP socket.
+//!
+//! [`RaknetSocket`](crate::RaknetSocket) and
+//! [`RaknetListener`](crate::RaknetListener) are generic over
+//! [`DatagramTransport`], defaulting to [`UdpTransport`]. Supplying a
+//! different implementation — for example one that frames each datagram
+//! inside a WebSocket binary message to a relay server — lets the whole
+//! reliability/ordering/fragmentation stack run without a publicly
+//! reachable UDP port, using virtual [`SocketAddr`]s to identify peers
+//! behind the relay.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::UdpSocket;
+
+/// A datagram-oriented transport: send a buffer to a peer address, receive a
+/// buffer and the address it came from, and report a local address.
+/// Implementations must be safely shareable across the tasks RakNet spawns
+/// per connection.
+pub trait DatagramTransport: Send + Sync + 'static {
+    /// Send `buf` to `target`.
+    fn send_to(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> impl std::future::Future<Output = io::Result<usize>> + Send;
+
+    /// Receive the next datagram, reporting the (possibly virtual) address
+    /// it was sent from.
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> impl std::future::Future<Output = io::Result<(usize, SocketAddr)>> + Send;
+
+    /// This side's own (possibly virtual) address.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// The default [`DatagramTransport`]: a thin wrapper over
+/// `tokio::net::UdpSocket`.
+pub struct UdpTransport(UdpSocket);
+
+impl UdpTransport {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self(UdpSocket::bind(addr).await?))
+    }
+}
+
+impl DatagramTransport for UdpTransport {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.0.send_to(buf, target).await
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.0.recv_from(buf).await
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arq::Reliability;
+    use crate::server::RaknetListener;
+    use crate::socket::RaknetSocket;
+    use tokio::sync::{mpsc, Mutex};
+
+    /// An in-memory [`DatagramTransport`], proving the reliability stack is
+    /// actually decoupled from `UdpSocket` and not just generic in name:
+    /// two instances created together with [`ChannelTransport::pair`]
+    /// exchange datagrams over an mpsc channel, with no socket involved.
+    struct ChannelTransport {
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+        tx: mpsc::UnboundedSender<Vec<u8>>,
+        rx: Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+    }
+
+    impl ChannelTransport {
+        fn pair(local_addr: SocketAddr, peer_addr: SocketAddr) -> (Self, Self) {
+            let (a_tx, a_rx) = mpsc::unbounded_channel();
+            let (b_tx, b_rx) = mpsc::unbounded_channel();
+            (
+                Self {
+                    local_addr,
+                    peer_addr,
+                    tx: b_tx,
+                    rx: Mutex::new(a_rx),
+                },
+                Self {
+                    local_addr: peer_addr,
+                    peer_addr: local_addr,
+                    tx: a_tx,
+                    rx: Mutex::new(b_rx),
+                },
+            )
+        }
+    }
+
+    impl DatagramTransport for ChannelTransport {
+        async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+            if target != self.peer_addr {
+                return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "unknown peer"));
+            }
+            self.tx
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "peer gone"))?;
+            Ok(buf.len())
+        }
+
+        async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let datagram = self
+                .rx
+                .lock()
+                .await
+                .recv()
+                .await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "peer gone"))?;
+            let n = datagram.len().min(buf.len());
+            buf[..n].copy_from_slice(&datagram[..n]);
+            Ok((n, self.peer_addr))
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(self.local_addr)
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_and_exchange_data_over_a_non_udp_transport() {
+        let server_addr: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let client_addr: SocketAddr = "10.0.0.2:1".parse().unwrap();
+        let (server_transport, client_transport) = ChannelTransport::pair(server_addr, client_addr);
+
+        let mut server = RaknetListener::bind_with_transport(server_transport)
+            .await
+            .unwrap();
+        server.listen().await;
+        tokio::spawn(async move {
+            let mut client1 = server.accept().await.unwrap();
+            let data = client1.recv().await.unwrap();
+            assert!(data == vec![1, 2, 3]);
+            client1.send(&[4, 5, 6], Reliability::Reliable).await.unwrap();
+        });
+
+        let mut client2 = RaknetSocket::connect_with_transport(client_transport, server_addr)
+            .await
+            .unwrap();
+        assert!(client2.peer_addr().unwrap() == server_addr);
+        client2.send(&[1, 2, 3], Reliability::Reliable).await.unwrap();
+        let buf = client2.recv().await.unwrap();
+        assert!(buf == vec![4, 5, 6]);
+    }
+}